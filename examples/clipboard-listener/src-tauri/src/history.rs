@@ -0,0 +1,124 @@
+use std::collections::VecDeque;
+
+use sha2::{Digest, Sha256};
+
+/// Number of entries kept before the oldest ones are dropped.
+pub const DEFAULT_MAX_SIZE: usize = 100;
+
+#[derive(Clone, serde::Serialize)]
+pub struct ClipboardEntry {
+    pub id: u64,
+    pub content: String,
+    /// hex encoded SHA-256 of `content`
+    pub content_hash: String,
+    /// milliseconds since the unix epoch
+    pub timestamp: u64,
+}
+
+/// Text the listener has emitted, newest last. Kept in app state, so it outlives the listener.
+pub struct ClipboardHistory {
+    entries: VecDeque<ClipboardEntry>,
+    max_size: usize,
+    next_id: u64,
+}
+
+impl Default for ClipboardHistory {
+    fn default() -> Self {
+        Self {
+            entries: VecDeque::new(),
+            max_size: DEFAULT_MAX_SIZE,
+            next_id: 1,
+        }
+    }
+}
+
+impl ClipboardHistory {
+    /// Adds an entry unless `content` has the same hash as the newest one, which is returned
+    /// instead.
+    pub fn push(&mut self, content: String, timestamp: u64) -> ClipboardEntry {
+        let content_hash = content_hash(&content);
+        if let Some(newest) = self.entries.back() {
+            if newest.content_hash == content_hash {
+                return newest.clone();
+            }
+        }
+        let entry = ClipboardEntry {
+            id: self.next_id,
+            content,
+            content_hash,
+            timestamp,
+        };
+        self.next_id += 1;
+        self.entries.push_back(entry.clone());
+        self.evict();
+        entry
+    }
+
+    /// Drops the oldest entries right away if there are more than `max_size`.
+    pub fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = max_size;
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        while self.entries.len() > self.max_size {
+            self.entries.pop_front();
+        }
+    }
+
+    /// Up to `limit` entries, newest first, skipping the `offset` newest.
+    pub fn page(&self, limit: usize, offset: usize) -> Vec<ClipboardEntry> {
+        self.entries
+            .iter()
+            .rev()
+            .skip(offset)
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Returns whether an entry with `id` was found.
+    pub fn remove(&mut self, id: u64) -> bool {
+        match self.entries.iter().position(|entry| entry.id == id) {
+            Some(index) => self.entries.remove(index).is_some(),
+            None => false,
+        }
+    }
+
+    /// The newest entry with this hash, as found in [`ClipboardEntry::content_hash`].
+    pub fn find_by_hash(&self, hash: &str) -> Option<ClipboardEntry> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|entry| entry.content_hash.eq_ignore_ascii_case(hash))
+            .cloned()
+    }
+
+    /// Entries containing `query`, ignoring case.
+    pub fn search(&self, query: &str) -> Vec<ClipboardEntry> {
+        let query = query.to_lowercase();
+        self.entries
+            .iter()
+            .filter(|entry| entry.content.to_lowercase().contains(&query))
+            .cloned()
+            .collect()
+    }
+
+    pub fn search_regex(&self, pattern: &str) -> Result<Vec<ClipboardEntry>, String> {
+        let regex = regex::Regex::new(pattern).map_err(|err| err.to_string())?;
+        Ok(self
+            .entries
+            .iter()
+            .filter(|entry| regex.is_match(&entry.content))
+            .cloned()
+            .collect())
+    }
+}
+
+pub fn content_hash(content: &str) -> String {
+    format!("{:x}", Sha256::digest(content.as_bytes()))
+}
@@ -0,0 +1,202 @@
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde::Serialize;
+
+/// Number of records `get_recent_logs` can return.
+pub const RECENT_CAPACITY: usize = 500;
+/// Size at which the log file is moved to `<name>.1` and a new one is started.
+const MAX_FILE_BYTES: u64 = 1024 * 1024;
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+#[derive(Clone, Serialize)]
+pub struct LogRecord {
+    pub level: String,
+    pub target: String,
+    pub message: String,
+    /// milliseconds since the unix epoch
+    pub timestamp: u64,
+}
+
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+}
+
+impl LogFile {
+    fn open(path: PathBuf) -> std::io::Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self { path, file, size })
+    }
+
+    fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        if self.size + line.len() as u64 > MAX_FILE_BYTES {
+            let mut rotated = self.path.clone().into_os_string();
+            rotated.push(".1");
+            fs::rename(&self.path, rotated)?;
+            self.file = File::create(&self.path)?;
+            self.size = 0;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+type Emitter = Arc<dyn Fn(&LogRecord) + Send + Sync>;
+
+/// Writes every record to stderr, the log file and the ring buffer, and hands warnings and
+/// errors to the emitter.
+pub struct Logger {
+    recent: Mutex<VecDeque<LogRecord>>,
+    file: Mutex<Option<LogFile>>,
+    emitter: Mutex<Option<Emitter>>,
+}
+
+impl Logger {
+    pub fn new() -> Self {
+        Self {
+            recent: Mutex::new(VecDeque::with_capacity(RECENT_CAPACITY)),
+            file: Mutex::new(None),
+            emitter: Mutex::new(None),
+        }
+    }
+
+    /// The app-wide logger. The first call installs it as the `log` facade's logger at `Info`.
+    pub fn install() -> &'static Logger {
+        let logger = LOGGER.get_or_init(Logger::new);
+        if log::set_logger(logger).is_ok() {
+            log::set_max_level(LevelFilter::Info);
+        }
+        logger
+    }
+
+    /// Appends to `path` from now on, rotating it once it grows past 1 MiB.
+    pub fn open_file(&self, path: PathBuf) -> std::io::Result<()> {
+        *self.file.lock().unwrap() = Some(LogFile::open(path)?);
+        Ok(())
+    }
+
+    /// Called with every record at `Warn` and above.
+    pub fn set_emitter<F: Fn(&LogRecord) + Send + Sync + 'static>(&self, emitter: F) {
+        *self.emitter.lock().unwrap() = Some(Arc::new(emitter));
+    }
+
+    /// Newest last, at most `limit` records.
+    pub fn recent(&self, limit: usize) -> Vec<LogRecord> {
+        let recent = self.recent.lock().unwrap();
+        let skip = recent.len().saturating_sub(limit);
+        recent.iter().skip(skip).cloned().collect()
+    }
+
+    fn push_recent(&self, entry: LogRecord) {
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() == RECENT_CAPACITY {
+            recent.pop_front();
+        }
+        recent.push_back(entry);
+    }
+}
+
+impl Default for Logger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let entry = LogRecord {
+            level: record.level().to_string(),
+            target: record.target().to_string(),
+            message: record.args().to_string(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_millis() as u64)
+                .unwrap_or_default(),
+        };
+        let line = format!(
+            "{} [{} {}] {}\n",
+            entry.timestamp, entry.level, entry.target, entry.message
+        );
+        eprint!("{}", line);
+        if let Some(file) = self.file.lock().unwrap().as_mut() {
+            // nowhere left to report this to but stderr
+            if let Err(err) = file.write_line(&line) {
+                eprintln!("Failed to write log file: {}", err);
+            }
+        }
+        self.push_recent(entry.clone());
+        if record.level() <= Level::Warn {
+            // cloned out so the emitter can log without deadlocking
+            let emitter = self.emitter.lock().unwrap().clone();
+            if let Some(emitter) = emitter {
+                emitter(&entry);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(file) = self.file.lock().unwrap().as_mut() {
+            let _ = file.file.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recent_keeps_the_newest_records() {
+        let logger = Logger::new();
+        for i in 0..RECENT_CAPACITY + 2 {
+            logger.push_recent(LogRecord {
+                level: Level::Info.to_string(),
+                target: "test".to_string(),
+                message: i.to_string(),
+                timestamp: 0,
+            });
+        }
+        let messages: Vec<String> = logger
+            .recent(2)
+            .into_iter()
+            .map(|record| record.message)
+            .collect();
+        assert_eq!(messages, ["500", "501"]);
+        assert_eq!(logger.recent(usize::MAX).len(), RECENT_CAPACITY);
+    }
+
+    #[test]
+    fn file_is_rotated_once_full() {
+        let dir =
+            std::env::temp_dir().join(format!("clipboard-listener-log-{}", std::process::id()));
+        let path = dir.join("test.log");
+        let mut file = LogFile::open(path.clone()).unwrap();
+        let line = "x".repeat(1000) + "\n";
+        for _ in 0..=MAX_FILE_BYTES / line.len() as u64 {
+            file.write_line(&line).unwrap();
+        }
+        assert!(dir.join("test.log.1").exists());
+        assert_eq!(fs::metadata(&path).unwrap().len(), line.len() as u64);
+        let _ = fs::remove_dir_all(dir);
+    }
+}
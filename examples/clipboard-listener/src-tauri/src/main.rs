@@ -1,64 +1,1035 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use arboard::Clipboard;
-use tauri::{Manager, Window};
+use base64::Engine;
+use tauri::{Manager, Runtime, Window};
+
+mod history;
+mod logging;
+mod telemetry;
+mod transform;
+mod watcher;
+
+use history::{ClipboardEntry, ClipboardHistory};
+use logging::{LogRecord, Logger};
+use telemetry::{EmitStats, EventStats};
+use transform::TransformStep;
+use watcher::{
+    command_fallback, ClipboardBackend, ClipboardContent, ClipboardImage, ClipboardSource,
+    ClipboardWatcher, NativeBackend, PollingBackend, WatchEvent,
+};
+
+/// Longest side of images sent to the frontend, larger clipboard images are downscaled.
+const MAX_IMAGE_SIDE: u32 = 1024;
+/// How long stopping a listener waits for its thread to exit.
+const STOP_TIMEOUT: Duration = Duration::from_secs(2);
+/// Writes the listener has not seen yet that are remembered, older ones are forgotten.
+const MAX_PENDING_SELF_WRITES: usize = 8;
 
 struct ClipboardListenerState {
-    clipboard_listener_running: Arc<Mutex<bool>>,
+    watcher: Mutex<Option<ClipboardWatcher>>,
+    /// Bumped whenever the listener starts or stops. Callbacks of an older listener whose
+    /// thread has not exited yet stop emitting once it changes.
+    generation: Arc<AtomicU64>,
+    listeners: Arc<Mutex<HashMap<String, NamedListener>>>,
+    transforms: Arc<Mutex<Vec<TransformStep>>>,
+    history: Arc<Mutex<ClipboardHistory>>,
+    /// Kept alive so content written by the app stays available on X11, where the clipboard
+    /// owner has to serve it.
+    writer: Mutex<Option<Clipboard>>,
+    /// Hashes of content written by `set_clipboard_text`/`set_clipboard_image` that the listener
+    /// has not seen yet, oldest first. A change matching one of them is skipped and drops it
+    /// with every older one, any other change clears them.
+    self_write: Arc<Mutex<VecDeque<u64>>>,
+}
+
+/// Settings of a named listener started with `start_named_listener`.
+#[derive(Clone, serde::Serialize)]
+struct ListenerConfig {
+    delay_ms: u64,
+    /// only changes containing this text are emitted
+    filter: Option<String>,
+}
+
+/// A named listener, polling independently of the others.
+struct NamedListener {
+    config: ListenerConfig,
+    watcher: ClipboardWatcher,
+}
+
+/// Returned by `list_named_listeners`.
+#[derive(serde::Serialize)]
+struct NamedListenerStatus {
+    #[serde(flatten)]
+    config: ListenerConfig,
+    /// false once the listener's thread has exited, e.g. after a panic
+    running: bool,
+}
+
+#[derive(Clone, serde::Serialize)]
+struct ClipboardMetrics {
+    updates_per_minute: f64,
+    total_updates: u64,
+    #[serde(skip)]
+    session_start: Instant,
+}
+
+struct ClipboardMetricsState {
+    metrics: Arc<Mutex<ClipboardMetrics>>,
+}
+
+struct TelemetryState {
+    emit_stats: Arc<EmitStats>,
+}
+
+struct LogState {
+    logger: &'static Logger,
+}
+
+#[derive(Clone, serde::Serialize)]
+struct ClipboardError {
+    message: String,
+    hint: Option<String>,
+    /// set when a named listener reports the error
+    listener: Option<String>,
+}
+
+#[derive(Clone, serde::Serialize)]
+struct ClipboardNonText {
+    kind: String,
+    /// set when a named listener saw the non-text content
+    listener: Option<String>,
+}
+
+/// Text is sent as `clipboard-update` and carries the id of its history entry, and the HTML
+/// version if there is one. Transforms only apply to the plain text. Images are sent as
+/// `clipboard-update-image`.
+#[derive(Clone, serde::Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum ClipboardPayload {
+    Text {
+        id: u64,
+        content: String,
+        html: Option<String>,
+        timestamp: u64,
+    },
+    Image {
+        width: u32,
+        height: u32,
+        base64_png: String,
+        timestamp: u64,
+    },
+}
+
+/// Downscales the image to `MAX_IMAGE_SIDE` and encodes it as base64 PNG.
+fn encode_png(clipboard_image: ClipboardImage) -> Result<(u32, u32, String), String> {
+    let rgba = image::RgbaImage::from_raw(
+        clipboard_image.width as u32,
+        clipboard_image.height as u32,
+        clipboard_image.bytes,
+    )
+    .ok_or("clipboard image has an unexpected size")?;
+    let longest_side = rgba.width().max(rgba.height());
+    let rgba = if longest_side > MAX_IMAGE_SIDE {
+        let scale = MAX_IMAGE_SIDE as f64 / longest_side as f64;
+        let width = ((rgba.width() as f64 * scale).round() as u32).max(1);
+        let height = ((rgba.height() as f64 * scale).round() as u32).max(1);
+        image::imageops::resize(&rgba, width, height, image::imageops::FilterType::Triangle)
+    } else {
+        rgba
+    };
+    let mut png = Vec::new();
+    rgba.write_to(
+        &mut std::io::Cursor::new(&mut png),
+        image::ImageOutputFormat::Png,
+    )
+    .map_err(|err| err.to_string())?;
+    Ok((
+        rgba.width(),
+        rgba.height(),
+        base64::engine::general_purpose::STANDARD.encode(png),
+    ))
+}
+
+/// Opens arboard, or `xclip`/`xsel` when asked to. Emits `clipboard-error` if neither works.
+fn open_source<R: Runtime>(
+    window: &Window<R>,
+    use_xclip_fallback: bool,
+) -> Option<ClipboardSource> {
+    match Clipboard::new() {
+        Ok(clipboard) => Some(ClipboardSource::Arboard(clipboard)),
+        Err(err) => {
+            let fallback = if use_xclip_fallback {
+                command_fallback()
+            } else {
+                None
+            };
+            if fallback.is_none() {
+                log::error!("Failed to open clipboard: {}", err);
+                let hint =
+                    cfg!(target_os = "linux").then(|| "install wl-clipboard package".to_string());
+                let _ = window.emit(
+                    "clipboard-error",
+                    ClipboardError {
+                        message: err.to_string(),
+                        hint,
+                        listener: None,
+                    },
+                );
+            }
+            fallback
+        }
+    }
+}
+
+/// Starts the watcher behind `listen_to_clipboard` and `listen_to_clipboard_native`.
+/// `history_size` replaces the history's max size, the entries themselves are kept.
+fn start_watcher<R: Runtime, B: ClipboardBackend>(
+    window: &Window<R>,
+    backend: B,
+    history_size: Option<usize>,
+    listener_state: &ClipboardListenerState,
+    metrics_state: &ClipboardMetricsState,
+    telemetry_state: &TelemetryState,
+) -> ClipboardWatcher {
+    if let Some(history_size) = history_size {
+        listener_state
+            .history
+            .lock()
+            .unwrap()
+            .set_max_size(history_size);
+    }
+    let metrics = metrics_state.metrics.clone();
+    let transforms = listener_state.transforms.clone();
+    let history = listener_state.history.clone();
+    let emit_stats = telemetry_state.emit_stats.clone();
+    let current_generation = listener_state.generation.clone();
+    let generation = current_generation.fetch_add(1, Ordering::SeqCst) + 1;
+    let self_write = listener_state.self_write.clone();
+    // a marker set before this listener started would otherwise swallow a later manual copy
+    self_write.lock().unwrap().clear();
+    let update_window = window.clone();
+    ClipboardWatcher::start(backend, move |event| {
+        if current_generation.load(Ordering::SeqCst) != generation {
+            return;
+        }
+        let emit_update = |event: &str, payload: ClipboardPayload| match emit_stats.emit(
+            &update_window,
+            event,
+            payload,
+        ) {
+            Ok(()) => metrics.lock().unwrap().total_updates += 1,
+            Err(err) => {
+                log::error!("Failed to emit {}: {}", event, err);
+                let _ = update_window.emit(
+                    "clipboard-error",
+                    ClipboardError {
+                        message: format!("Failed to emit {}: {}", event, err),
+                        hint: None,
+                        listener: None,
+                    },
+                );
+            }
+        };
+        match event {
+            WatchEvent::Changed(change) => {
+                {
+                    let mut self_write = self_write.lock().unwrap();
+                    // only hash when there is something to compare with, images can be large
+                    if !self_write.is_empty() {
+                        let hash = match &change.content {
+                            ClipboardContent::Text { text, .. } => watcher::text_hash(text),
+                            ClipboardContent::Image(image) => watcher::image_hash(image),
+                        };
+                        match self_write.iter().position(|pending| *pending == hash) {
+                            Some(position) => {
+                                self_write.drain(..=position);
+                                return;
+                            }
+                            None => self_write.clear(),
+                        }
+                    }
+                }
+                match change.content {
+                    ClipboardContent::Text { text, html } => {
+                        let text = transform::apply(&transforms.lock().unwrap(), text);
+                        let entry = history.lock().unwrap().push(text, change.timestamp);
+                        let payload = ClipboardPayload::Text {
+                            id: entry.id,
+                            content: entry.content,
+                            html,
+                            timestamp: change.timestamp,
+                        };
+                        emit_update("clipboard-update", payload);
+                    }
+                    ClipboardContent::Image(clipboard_image) => match encode_png(clipboard_image) {
+                        Ok((width, height, base64_png)) => {
+                            let payload = ClipboardPayload::Image {
+                                width,
+                                height,
+                                base64_png,
+                                timestamp: change.timestamp,
+                            };
+                            emit_update("clipboard-update-image", payload);
+                        }
+                        Err(message) => {
+                            let _ = update_window.emit(
+                                "clipboard-error",
+                                ClipboardError {
+                                    message,
+                                    hint: None,
+                                    listener: None,
+                                },
+                            );
+                        }
+                    },
+                }
+            }
+            WatchEvent::NonText => {
+                let _ = update_window.emit(
+                    "clipboard-non-text",
+                    ClipboardNonText {
+                        kind: "non-text".to_string(),
+                        listener: None,
+                    },
+                );
+            }
+            WatchEvent::Error(message) => {
+                log::warn!("Failed to read clipboard: {}", message);
+                let _ = update_window.emit(
+                    "clipboard-error",
+                    ClipboardError {
+                        message,
+                        hint: None,
+                        listener: None,
+                    },
+                );
+            }
+        }
+    })
+}
+
+/// Stops the running listener, if any, and starts one on `backend`. Shared by both listen
+/// commands, so calling either while a listener runs never leaves two threads emitting.
+fn start_listener<R: Runtime, B: ClipboardBackend>(
+    window: &Window<R>,
+    backend: B,
+    history_size: Option<usize>,
+    listener_state: &ClipboardListenerState,
+    metrics_state: &ClipboardMetricsState,
+    telemetry_state: &TelemetryState,
+) {
+    // hold the slot until the new watcher is stored, two concurrent calls must not both start one
+    let mut watcher_slot = listener_state.watcher.lock().unwrap();
+    if let Some(mut watcher) = watcher_slot.take() {
+        log::info!("Restarting Clipboard listener");
+        stop_watcher(listener_state, &mut watcher);
+    }
+    let watcher = start_watcher(
+        window,
+        backend,
+        history_size,
+        listener_state,
+        metrics_state,
+        telemetry_state,
+    );
+    *watcher_slot = Some(watcher);
+    let _ = window.emit("clipboard_listener_running", true);
 }
 
 #[tauri::command]
-fn listen_to_clipboard(
-    window: Window,
+#[allow(clippy::too_many_arguments)]
+fn listen_to_clipboard<R: Runtime>(
+    window: Window<R>,
     delay_millis: u64,
+    use_xclip_fallback: Option<bool>,
+    watch_images: Option<bool>,
+    history_size: Option<usize>,
+    listener_state: tauri::State<'_, ClipboardListenerState>,
+    metrics_state: tauri::State<'_, ClipboardMetricsState>,
+    telemetry_state: tauri::State<'_, TelemetryState>,
+) {
+    log::info!("Start Clipboard listening");
+    let source = match open_source(&window, use_xclip_fallback.unwrap_or(false)) {
+        Some(source) => source,
+        None => {
+            let _ = window.emit(
+                "clipboard_listener_running",
+                listener_running(&listener_state),
+            );
+            return;
+        }
+    };
+    let watch_images = watch_images.unwrap_or(false);
+    let backend =
+        PollingBackend::new(source, Duration::from_millis(delay_millis)).with_images(watch_images);
+    start_listener(
+        &window,
+        backend,
+        history_size,
+        &listener_state,
+        &metrics_state,
+        &telemetry_state,
+    );
+}
+
+/// Like `listen_to_clipboard`, but reads the clipboard only when the OS reports a change.
+/// Emits `clipboard_listener_mode` with `"native"` or `"polling"`, the latter when
+/// notifications are unavailable and the clipboard is polled every `fallback_delay_millis`.
+#[tauri::command]
+fn listen_to_clipboard_native<R: Runtime>(
+    window: Window<R>,
+    fallback_delay_millis: Option<u64>,
+    watch_images: Option<bool>,
+    history_size: Option<usize>,
+    listener_state: tauri::State<'_, ClipboardListenerState>,
+    metrics_state: tauri::State<'_, ClipboardMetricsState>,
+    telemetry_state: tauri::State<'_, TelemetryState>,
+) {
+    log::info!("Start native Clipboard listening");
+    let source = match open_source(&window, false) {
+        Some(source) => source,
+        None => {
+            let _ = window.emit(
+                "clipboard_listener_running",
+                listener_running(&listener_state),
+            );
+            return;
+        }
+    };
+    let watch_images = watch_images.unwrap_or(false);
+    let polling = PollingBackend::new(
+        source,
+        Duration::from_millis(fallback_delay_millis.unwrap_or(100)),
+    )
+    .with_images(watch_images);
+    if watcher::native_notifications_supported() {
+        let fallback_window = window.clone();
+        let backend = NativeBackend::new(polling, move |reason| {
+            log::warn!(
+                "Clipboard notifications unavailable, polling instead: {}",
+                reason
+            );
+            let _ = fallback_window.emit("clipboard_listener_mode", "polling");
+        });
+        let _ = window.emit("clipboard_listener_mode", "native");
+        start_listener(
+            &window,
+            backend,
+            history_size,
+            &listener_state,
+            &metrics_state,
+            &telemetry_state,
+        );
+    } else {
+        let _ = window.emit("clipboard_listener_mode", "polling");
+        start_listener(
+            &window,
+            polling,
+            history_size,
+            &listener_state,
+            &metrics_state,
+            &telemetry_state,
+        );
+    }
+}
+
+/// Stops the watcher of the main listener, its callbacks no longer emit once this returns.
+/// Returns whether the thread has exited.
+fn stop_watcher(listener_state: &ClipboardListenerState, watcher: &mut ClipboardWatcher) -> bool {
+    listener_state.generation.fetch_add(1, Ordering::SeqCst);
+    let stopped = watcher.stop(STOP_TIMEOUT);
+    if stopped {
+        log::info!("Clipboard Listener stopped running");
+    } else {
+        log::warn!("Clipboard listener thread has not exited yet, it will exit on its own");
+    }
+    stopped
+}
+
+#[tauri::command]
+fn stop_clipboard_listener<R: Runtime>(
+    window: Window<R>,
     listener_state: tauri::State<'_, ClipboardListenerState>,
 ) {
-    println!("Start Clipboard listening");
-    let clipboard = Arc::new(Mutex::new(Clipboard::new().unwrap()));
-    let content = clipboard.lock().unwrap().get_text().unwrap();
-    let content = Arc::new(Mutex::new(content));
-    let clipboard = Arc::clone(&clipboard);
-    let content = Arc::clone(&content);
-    let mut running = listener_state.clipboard_listener_running.lock().unwrap();
-    *running = true;
-    let _ = window.emit("clipboard_listener_running", *running);
-    let running = listener_state.clipboard_listener_running.clone();
-
-    std::thread::spawn(move || loop {
-        let mut cb = clipboard.lock().unwrap();
-        let cur_text = cb.get_text().unwrap();
-        let mut pre_text = content.lock().unwrap();
-        if !*running.lock().unwrap() {
-            println!("Clipboard Listener stopped running");
+    log::info!("stop_clipboard_listener called");
+    let mut watcher_slot = listener_state.watcher.lock().unwrap();
+    let watcher = match watcher_slot.as_mut() {
+        Some(watcher) => watcher,
+        None => {
             let _ = window.emit("clipboard_listener_running", false);
             return;
         }
-        if cur_text != *pre_text {
-            *pre_text = cur_text.clone();
-            window.emit("clipboard-update", cur_text).unwrap();
+    };
+    if stop_watcher(&listener_state, watcher) {
+        *watcher_slot = None;
+        let _ = window.emit("clipboard_listener_running", false);
+        return;
+    }
+    // keep it in the slot so it still counts as running, and report the stop once it happens
+    let current_generation = listener_state.generation.clone();
+    let generation = current_generation.load(Ordering::SeqCst);
+    watcher.on_exit(move || {
+        // a listener started in the meantime has already reported itself as running
+        if current_generation.load(Ordering::SeqCst) == generation {
+            let _ = window.emit("clipboard_listener_running", false);
         }
-        std::thread::sleep(std::time::Duration::from_millis(delay_millis));
     });
 }
 
+fn listener_running(listener_state: &ClipboardListenerState) -> bool {
+    matches!(&*listener_state.watcher.lock().unwrap(), Some(watcher) if !watcher.has_exited())
+}
+
 #[tauri::command]
-fn stop_clipboard_listener(listener_state: tauri::State<'_, ClipboardListenerState>) {
-    println!("stop_clipboard_listener called");
-    let mut running = listener_state.clipboard_listener_running.lock().unwrap();
-    *running = false;
+fn is_clipboard_listener_running(listener_state: tauri::State<'_, ClipboardListenerState>) -> bool {
+    listener_running(&listener_state)
 }
 
-fn main() {
-    tauri::Builder::default()
+/// Writes through the app's own arboard instance after setting the self-write marker.
+fn write_clipboard<F>(
+    listener_state: &ClipboardListenerState,
+    hash: u64,
+    write: F,
+) -> Result<(), String>
+where
+    F: FnOnce(&mut Clipboard) -> Result<(), arboard::Error>,
+{
+    let mut writer = listener_state.writer.lock().unwrap();
+    if writer.is_none() {
+        *writer = Some(Clipboard::new().map_err(|err| err.to_string())?);
+    }
+    // set before writing, the listener may read the new content before `write` returns
+    {
+        let mut self_write = listener_state.self_write.lock().unwrap();
+        if self_write.len() == MAX_PENDING_SELF_WRITES {
+            self_write.pop_front();
+        }
+        self_write.push_back(hash);
+    }
+    if let Err(err) = write(writer.as_mut().unwrap()) {
+        let mut self_write = listener_state.self_write.lock().unwrap();
+        if let Some(position) = self_write.iter().rposition(|pending| *pending == hash) {
+            self_write.remove(position);
+        }
+        return Err(err.to_string());
+    }
+    Ok(())
+}
+
+/// With `html`, both versions are placed on the clipboard in one write and `text` is what
+/// apps that do not read HTML paste.
+#[tauri::command]
+fn set_clipboard_text(
+    text: String,
+    html: Option<String>,
+    listener_state: tauri::State<'_, ClipboardListenerState>,
+) -> Result<(), String> {
+    let hash = watcher::text_hash(&text);
+    write_clipboard(&listener_state, hash, |clipboard| match html {
+        Some(html) => clipboard.set_html(html, Some(text)),
+        None => clipboard.set_text(text),
+    })
+}
+
+#[tauri::command]
+fn set_clipboard_image(
+    base64_png: String,
+    listener_state: tauri::State<'_, ClipboardListenerState>,
+) -> Result<(), String> {
+    let png = base64::engine::general_purpose::STANDARD
+        .decode(base64_png.trim())
+        .map_err(|err| err.to_string())?;
+    let rgba = image::load_from_memory_with_format(&png, image::ImageFormat::Png)
+        .map_err(|err| err.to_string())?
+        .into_rgba8();
+    let clipboard_image = ClipboardImage {
+        width: rgba.width() as usize,
+        height: rgba.height() as usize,
+        bytes: rgba.into_raw(),
+    };
+    let hash = watcher::image_hash(&clipboard_image);
+    write_clipboard(&listener_state, hash, |clipboard| {
+        clipboard.set_image(arboard::ImageData {
+            width: clipboard_image.width,
+            height: clipboard_image.height,
+            bytes: clipboard_image.bytes.into(),
+        })
+    })
+}
+
+#[tauri::command]
+fn start_named_listener<R: Runtime>(
+    window: Window<R>,
+    name: String,
+    delay_ms: u64,
+    filter: Option<String>,
+    listener_state: tauri::State<'_, ClipboardListenerState>,
+    telemetry_state: tauri::State<'_, TelemetryState>,
+) -> Result<(), String> {
+    if name == "image" {
+        return Err("\"image\" is reserved for clipboard-update-image".to_string());
+    }
+    // tauri panics when emitting an event whose name has other characters
+    let valid_char = |c: char| c.is_ascii_alphanumeric() || matches!(c, '-' | '/' | ':' | '_');
+    if name.is_empty() || !name.chars().all(valid_char) {
+        return Err(format!(
+            "Invalid listener name \"{}\", use letters, digits, '-', '/', ':' and '_'",
+            name
+        ));
+    }
+    let mut listeners = listener_state.listeners.lock().unwrap();
+    if listeners.contains_key(&name) {
+        return Err(format!("Listener \"{}\" is already running", name));
+    }
+    let clipboard = Clipboard::new().map_err(|err| err.to_string())?;
+    let config = ListenerConfig { delay_ms, filter };
+    let filter = config.filter.clone();
+    let transforms = listener_state.transforms.clone();
+    let emit_stats = telemetry_state.emit_stats.clone();
+    let event = format!("clipboard-update-{}", name);
+    log::info!("Start named clipboard listener: {}", name);
+    let backend = PollingBackend::new(
+        ClipboardSource::Arboard(clipboard),
+        Duration::from_millis(delay_ms),
+    );
+    let listener_name = name.clone();
+    let report_error = move |window: &Window<R>, message: String| {
+        log::warn!("Named clipboard listener {}: {}", listener_name, message);
+        let _ = window.emit(
+            "clipboard-error",
+            ClipboardError {
+                message,
+                hint: None,
+                listener: Some(listener_name.clone()),
+            },
+        );
+    };
+    let listener_name = name.clone();
+    let watcher = ClipboardWatcher::start(backend, move |watch_event| match watch_event {
+        WatchEvent::Changed(change) => {
+            let text = match change.content {
+                ClipboardContent::Text { text, .. } => text,
+                ClipboardContent::Image(_) => return,
+            };
+            let matches = match &filter {
+                Some(filter) => text.contains(filter.as_str()),
+                None => true,
+            };
+            if matches {
+                let text = transform::apply(&transforms.lock().unwrap(), text);
+                if let Err(err) = emit_stats.emit(&window, &event, text) {
+                    report_error(&window, format!("Failed to emit {}: {}", event, err));
+                }
+            }
+        }
+        WatchEvent::NonText => {
+            let _ = window.emit(
+                "clipboard-non-text",
+                ClipboardNonText {
+                    kind: "non-text".to_string(),
+                    listener: Some(listener_name.clone()),
+                },
+            );
+        }
+        WatchEvent::Error(message) => {
+            report_error(&window, format!("Failed to read clipboard: {}", message));
+        }
+    });
+    listeners.insert(name, NamedListener { config, watcher });
+    Ok(())
+}
+
+#[tauri::command]
+fn stop_named_listener(name: String, listener_state: tauri::State<'_, ClipboardListenerState>) {
+    log::info!("stop_named_listener called: {}", name);
+    let listener = listener_state.listeners.lock().unwrap().remove(&name);
+    if let Some(mut listener) = listener {
+        listener.watcher.stop(STOP_TIMEOUT);
+    }
+}
+
+#[tauri::command]
+fn list_named_listeners(
+    listener_state: tauri::State<'_, ClipboardListenerState>,
+) -> HashMap<String, NamedListenerStatus> {
+    listener_state
+        .listeners
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(name, listener)| {
+            let status = NamedListenerStatus {
+                config: listener.config.clone(),
+                running: !listener.watcher.has_exited(),
+            };
+            (name.clone(), status)
+        })
+        .collect()
+}
+
+#[tauri::command]
+fn set_transforms(
+    steps: Vec<TransformStep>,
+    listener_state: tauri::State<'_, ClipboardListenerState>,
+) -> Result<(), String> {
+    transform::validate(&steps)?;
+    *listener_state.transforms.lock().unwrap() = steps;
+    Ok(())
+}
+
+/// Newest first. Without `limit` every entry after `offset` is returned.
+#[tauri::command]
+fn get_clipboard_history(
+    limit: Option<usize>,
+    offset: Option<usize>,
+    listener_state: tauri::State<'_, ClipboardListenerState>,
+) -> Vec<ClipboardEntry> {
+    listener_state
+        .history
+        .lock()
+        .unwrap()
+        .page(limit.unwrap_or(usize::MAX), offset.unwrap_or(0))
+}
+
+#[tauri::command]
+fn clear_clipboard_history(listener_state: tauri::State<'_, ClipboardListenerState>) {
+    listener_state.history.lock().unwrap().clear();
+}
+
+#[tauri::command]
+fn delete_clipboard_entry(
+    id: u64,
+    listener_state: tauri::State<'_, ClipboardListenerState>,
+) -> Result<(), String> {
+    if listener_state.history.lock().unwrap().remove(id) {
+        Ok(())
+    } else {
+        Err(format!("No clipboard history entry with id {}", id))
+    }
+}
+
+#[tauri::command]
+fn find_by_hash(
+    hash: String,
+    listener_state: tauri::State<'_, ClipboardListenerState>,
+) -> Option<ClipboardEntry> {
+    listener_state.history.lock().unwrap().find_by_hash(&hash)
+}
+
+#[tauri::command]
+fn search_clipboard_history(
+    query: String,
+    listener_state: tauri::State<'_, ClipboardListenerState>,
+) -> Vec<ClipboardEntry> {
+    listener_state.history.lock().unwrap().search(&query)
+}
+
+#[tauri::command]
+fn search_clipboard_history_regex(
+    pattern: String,
+    listener_state: tauri::State<'_, ClipboardListenerState>,
+) -> Result<Vec<ClipboardEntry>, String> {
+    listener_state
+        .history
+        .lock()
+        .unwrap()
+        .search_regex(&pattern)
+}
+
+#[tauri::command]
+fn get_emit_stats(
+    window_secs: u64,
+    telemetry_state: tauri::State<'_, TelemetryState>,
+) -> Vec<EventStats> {
+    telemetry_state.emit_stats.stats(window_secs)
+}
+
+#[tauri::command]
+fn reset_emit_stats(telemetry_state: tauri::State<'_, TelemetryState>) {
+    telemetry_state.emit_stats.reset();
+}
+
+#[tauri::command]
+fn set_emit_stats_enabled(enabled: bool, telemetry_state: tauri::State<'_, TelemetryState>) {
+    telemetry_state.emit_stats.set_enabled(enabled);
+}
+
+#[tauri::command]
+fn get_clipboard_metrics(
+    metrics_state: tauri::State<'_, ClipboardMetricsState>,
+) -> ClipboardMetrics {
+    metrics_state.metrics.lock().unwrap().clone()
+}
+
+/// `level` is one of `off`, `error`, `warn`, `info`, `debug` or `trace`.
+#[tauri::command]
+fn set_log_level(level: String) -> Result<(), String> {
+    let level = level
+        .parse::<log::LevelFilter>()
+        .map_err(|_| format!("Unknown log level \"{}\"", level))?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+/// The newest `limit` records, oldest first.
+#[tauri::command]
+fn get_recent_logs(limit: Option<usize>, log_state: tauri::State<'_, LogState>) -> Vec<LogRecord> {
+    log_state
+        .logger
+        .recent(limit.unwrap_or(logging::RECENT_CAPACITY))
+}
+
+/// Registers the state, the metrics thread and the commands.
+fn build_app<R: Runtime>(builder: tauri::Builder<R>) -> tauri::Builder<R> {
+    builder
         .manage(ClipboardListenerState {
-            clipboard_listener_running: Arc::new(Mutex::new(false)),
+            watcher: Mutex::new(None),
+            generation: Arc::new(AtomicU64::new(0)),
+            listeners: Arc::new(Mutex::new(HashMap::new())),
+            transforms: Arc::new(Mutex::new(Vec::new())),
+            history: Arc::new(Mutex::new(ClipboardHistory::default())),
+            writer: Mutex::new(None),
+            self_write: Arc::new(Mutex::new(VecDeque::new())),
+        })
+        .manage(ClipboardMetricsState {
+            metrics: Arc::new(Mutex::new(ClipboardMetrics {
+                updates_per_minute: 0.0,
+                total_updates: 0,
+                session_start: Instant::now(),
+            })),
+        })
+        .manage(TelemetryState {
+            emit_stats: Arc::new(EmitStats::new(false)),
+        })
+        .manage(LogState {
+            logger: Logger::install(),
+        })
+        .setup(|app| {
+            let logger = app.state::<LogState>().logger;
+            // without an identifier (the mock runtime) the log dir is not specific to this app
+            let log_dir = if app.config().tauri.bundle.identifier.is_empty() {
+                None
+            } else {
+                app.path_resolver().app_log_dir()
+            };
+            if let Some(log_dir) = log_dir {
+                if let Err(err) = logger.open_file(log_dir.join("clipboard-listener.log")) {
+                    log::error!("Failed to open log file in {}: {}", log_dir.display(), err);
+                }
+            }
+            let log_handle = app.handle();
+            logger.set_emitter(move |record| {
+                let _ = log_handle.emit_all("log-event", record);
+            });
+            let app_handle = app.handle();
+            // recompute the rate once a minute and broadcast it to every window
+            std::thread::spawn(move || {
+                let mut last_total = 0;
+                loop {
+                    std::thread::sleep(Duration::from_secs(60));
+                    let metrics_state = app_handle.state::<ClipboardMetricsState>();
+                    let snapshot = {
+                        let mut metrics = metrics_state.metrics.lock().unwrap();
+                        metrics.updates_per_minute = (metrics.total_updates - last_total) as f64;
+                        last_total = metrics.total_updates;
+                        metrics.clone()
+                    };
+                    log::info!(
+                        "clipboard metrics: updates_per_minute={} total_updates={} session_secs={}",
+                        snapshot.updates_per_minute,
+                        snapshot.total_updates,
+                        snapshot.session_start.elapsed().as_secs()
+                    );
+                    let _ = app_handle.emit_all("clipboard-metrics", snapshot);
+                }
+            });
+            Ok(())
         })
         .invoke_handler(tauri::generate_handler![
             listen_to_clipboard,
+            listen_to_clipboard_native,
+            set_clipboard_text,
+            set_clipboard_image,
             stop_clipboard_listener,
+            is_clipboard_listener_running,
+            start_named_listener,
+            stop_named_listener,
+            list_named_listeners,
+            set_transforms,
+            get_clipboard_history,
+            clear_clipboard_history,
+            delete_clipboard_entry,
+            find_by_hash,
+            search_clipboard_history,
+            search_clipboard_history_regex,
+            get_emit_stats,
+            reset_emit_stats,
+            set_emit_stats_enabled,
+            get_clipboard_metrics,
+            set_log_level,
+            get_recent_logs,
         ])
+}
+
+fn main() {
+    build_app(tauri::Builder::default())
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::{channel, Receiver, Sender};
+    use std::thread;
+
+    use serde_json::{json, Value};
+    use tauri::api::ipc::CallbackFn;
+    use tauri::test::{mock_builder, mock_context, noop_assets, MockRuntime, INVOKE_KEY};
+    use tauri::{App, InvokePayload};
+
+    use super::*;
+    use watcher::{ReadError, StopSignal};
+
+    type Read = Result<ClipboardContent, ReadError>;
+
+    /// Passes on whatever the test sends, in place of a real clipboard.
+    struct ScriptedBackend(Receiver<Read>);
+
+    impl ClipboardBackend for ScriptedBackend {
+        fn watch(&mut self, stop: &StopSignal, on_read: &mut dyn FnMut(Read)) {
+            while !stop.is_stopped() {
+                if let Ok(read) = self.0.recv_timeout(Duration::from_millis(10)) {
+                    on_read(read);
+                }
+            }
+        }
+    }
+
+    fn text(text: &str) -> Read {
+        Ok(ClipboardContent::Text {
+            text: text.to_string(),
+            html: None,
+        })
+    }
+
+    fn mock_app() -> (App<MockRuntime>, Window<MockRuntime>) {
+        let app = build_app(mock_builder())
+            .build(mock_context(noop_assets()))
+            .unwrap();
+        let window = app.get_window("main").unwrap();
+        (app, window)
+    }
+
+    fn invoke(window: &Window<MockRuntime>, cmd: &str, args: Value) -> Result<Value, Value> {
+        tauri::test::get_ipc_response(
+            window,
+            InvokePayload {
+                cmd: cmd.into(),
+                tauri_module: None,
+                callback: CallbackFn(0),
+                error: CallbackFn(1),
+                inner: args,
+                invoke_key: Some(INVOKE_KEY.into()),
+            },
+        )
+    }
+
+    fn start_scripted(app: &App<MockRuntime>, window: &Window<MockRuntime>) -> Sender<Read> {
+        let (sender, receiver) = channel();
+        start_listener(
+            window,
+            ScriptedBackend(receiver),
+            None,
+            &app.state(),
+            &app.state(),
+            &app.state(),
+        );
+        sender
+    }
+
+    fn total_updates(window: &Window<MockRuntime>) -> u64 {
+        let metrics = invoke(window, "get_clipboard_metrics", json!({})).unwrap();
+        metrics["total_updates"].as_u64().unwrap()
+    }
+
+    fn wait_for_updates(window: &Window<MockRuntime>, expected: u64) {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while total_updates(window) < expected && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        // give a second, duplicate emit the chance to show up
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(total_updates(window), expected);
+    }
+
+    fn is_running(window: &Window<MockRuntime>) -> bool {
+        invoke(window, "is_clipboard_listener_running", json!({})) == Ok(json!(true))
+    }
+
+    #[test]
+    fn listener_status_follows_start_and_stop() {
+        let (app, window) = mock_app();
+        assert!(!is_running(&window));
+
+        let _sender = start_scripted(&app, &window);
+        assert!(is_running(&window));
+
+        assert_eq!(
+            invoke(&window, "stop_clipboard_listener", json!({})),
+            Ok(Value::Null)
+        );
+        assert!(!is_running(&window));
+    }
+
+    #[test]
+    fn starting_twice_reports_each_change_once() {
+        let (app, window) = mock_app();
+        let first = start_scripted(&app, &window);
+        first.send(text("a")).unwrap();
+
+        let second = start_scripted(&app, &window);
+        // the first watcher was stopped and joined, its backend is gone
+        assert!(first.send(text("b")).is_err());
+
+        second.send(text("a")).unwrap();
+        second.send(text("b")).unwrap();
+        wait_for_updates(&window, 1);
+        second.send(text("c")).unwrap();
+        wait_for_updates(&window, 2);
+    }
+
+    #[test]
+    fn named_listener_rejects_invalid_names() {
+        let (_app, window) = mock_app();
+        for name in ["", "has space", "dot.ted", "image"] {
+            let result = invoke(
+                &window,
+                "start_named_listener",
+                json!({ "name": name, "delayMs": 100 }),
+            );
+            assert!(result.is_err(), "{:?} was accepted", name);
+        }
+    }
+
+    #[test]
+    fn quick_self_writes_are_not_echoed() {
+        let (app, window) = mock_app();
+        let sender = start_scripted(&app, &window);
+        sender.send(text("x")).unwrap();
+        app.state::<ClipboardListenerState>()
+            .self_write
+            .lock()
+            .unwrap()
+            .extend([watcher::text_hash("A"), watcher::text_hash("B")]);
+        sender.send(text("A")).unwrap();
+        sender.send(text("B")).unwrap();
+        sender.send(text("C")).unwrap();
+        wait_for_updates(&window, 1);
+    }
+}
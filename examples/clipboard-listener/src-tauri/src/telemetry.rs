@@ -0,0 +1,199 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use serde::Serialize;
+use tauri::{Runtime, Window};
+
+/// How many one-second buckets are kept, i.e. the longest window `get_emit_stats` can cover.
+pub const MAX_WINDOW_SECS: u64 = 300;
+
+#[derive(Default)]
+struct EventBucket {
+    count: u64,
+    bytes: u64,
+    latencies_us: Vec<u64>,
+}
+
+struct SecondBucket {
+    second: u64,
+    events: HashMap<String, EventBucket>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct EventStats {
+    pub event: String,
+    pub events_per_sec: f64,
+    pub bytes_per_sec: f64,
+    pub p50_latency_ms: f64,
+    pub p95_latency_ms: f64,
+}
+
+/// Records count, payload size and time spent in `emit` per event name.
+///
+/// When disabled, [`EmitStats::emit`] is a plain `window.emit` apart from one atomic load.
+pub struct EmitStats {
+    enabled: AtomicBool,
+    started: Instant,
+    buckets: Mutex<VecDeque<SecondBucket>>,
+}
+
+impl EmitStats {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled: AtomicBool::new(enabled),
+            started: Instant::now(),
+            buckets: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn emit<R: Runtime, S: Serialize + Clone>(
+        &self,
+        window: &Window<R>,
+        event: &str,
+        payload: S,
+    ) -> tauri::Result<()> {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return window.emit(event, payload);
+        }
+        let bytes = serde_json::to_vec(&payload).map_or(0, |json| json.len() as u64);
+        let start = Instant::now();
+        let result = window.emit(event, payload);
+        self.record(event, bytes, start.elapsed().as_micros() as u64);
+        result
+    }
+
+    fn record(&self, event: &str, bytes: u64, latency_us: u64) {
+        self.record_at(self.started.elapsed().as_secs(), event, bytes, latency_us);
+    }
+
+    /// `second` counts from `started`.
+    fn record_at(&self, second: u64, event: &str, bytes: u64, latency_us: u64) {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.back().map(|bucket| bucket.second) != Some(second) {
+            buckets.push_back(SecondBucket {
+                second,
+                events: HashMap::new(),
+            });
+        }
+        let oldest = second.saturating_sub(MAX_WINDOW_SECS - 1);
+        while matches!(buckets.front(), Some(bucket) if bucket.second < oldest) {
+            buckets.pop_front();
+        }
+        let bucket = buckets
+            .back_mut()
+            .unwrap()
+            .events
+            .entry(event.to_string())
+            .or_default();
+        bucket.count += 1;
+        bucket.bytes += bytes;
+        bucket.latencies_us.push(latency_us);
+    }
+
+    /// Aggregates the last `window_secs` seconds (clamped to `1..=MAX_WINDOW_SECS`) per event name.
+    pub fn stats(&self, window_secs: u64) -> Vec<EventStats> {
+        self.stats_at(self.started.elapsed().as_secs(), window_secs)
+    }
+
+    fn stats_at(&self, now: u64, window_secs: u64) -> Vec<EventStats> {
+        let window_secs = window_secs.clamp(1, MAX_WINDOW_SECS);
+        let mut totals: HashMap<&str, EventBucket> = HashMap::new();
+        let buckets = self.buckets.lock().unwrap();
+        for bucket in buckets
+            .iter()
+            .filter(|bucket| bucket.second + window_secs > now)
+        {
+            for (event, stats) in &bucket.events {
+                let total = totals.entry(event.as_str()).or_default();
+                total.count += stats.count;
+                total.bytes += stats.bytes;
+                total.latencies_us.extend_from_slice(&stats.latencies_us);
+            }
+        }
+        let mut stats: Vec<EventStats> = totals
+            .into_iter()
+            .map(|(event, mut total)| {
+                total.latencies_us.sort_unstable();
+                EventStats {
+                    event: event.to_string(),
+                    events_per_sec: total.count as f64 / window_secs as f64,
+                    bytes_per_sec: total.bytes as f64 / window_secs as f64,
+                    p50_latency_ms: percentile_ms(&total.latencies_us, 0.5),
+                    p95_latency_ms: percentile_ms(&total.latencies_us, 0.95),
+                }
+            })
+            .collect();
+        stats.sort_by(|a, b| a.event.cmp(&b.event));
+        stats
+    }
+
+    pub fn reset(&self) {
+        self.buckets.lock().unwrap().clear();
+    }
+}
+
+fn percentile_ms(sorted_us: &[u64], percentile: f64) -> f64 {
+    if sorted_us.is_empty() {
+        return 0.0;
+    }
+    let index = ((sorted_us.len() - 1) as f64 * percentile).round() as usize;
+    sorted_us[index] as f64 / 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seconds(stats: &EmitStats) -> Vec<u64> {
+        let buckets = stats.buckets.lock().unwrap();
+        buckets.iter().map(|bucket| bucket.second).collect()
+    }
+
+    #[test]
+    fn old_buckets_are_evicted() {
+        let stats = EmitStats::new(true);
+        stats.record_at(0, "a", 1, 1);
+        stats.record_at(0, "a", 1, 1);
+        stats.record_at(MAX_WINDOW_SECS - 1, "a", 1, 1);
+        assert_eq!(seconds(&stats), [0, MAX_WINDOW_SECS - 1]);
+        stats.record_at(MAX_WINDOW_SECS, "a", 1, 1);
+        assert_eq!(seconds(&stats), [MAX_WINDOW_SECS - 1, MAX_WINDOW_SECS]);
+    }
+
+    #[test]
+    fn window_is_clamped_and_filters_old_buckets() {
+        let stats = EmitStats::new(true);
+        stats.record_at(5, "a", 10, 1);
+        for _ in 0..6 {
+            stats.record_at(10, "a", 10, 1);
+        }
+
+        // the bucket at 5 is outside the last 5 seconds
+        let recent = stats.stats_at(10, 5);
+        assert_eq!(recent[0].events_per_sec, 6.0 / 5.0);
+        assert_eq!(recent[0].bytes_per_sec, 60.0 / 5.0);
+
+        assert_eq!(stats.stats_at(10, 0)[0].events_per_sec, 6.0);
+        let clamped = stats.stats_at(10, 10 * MAX_WINDOW_SECS);
+        assert_eq!(clamped[0].events_per_sec, 7.0 / MAX_WINDOW_SECS as f64);
+    }
+
+    #[test]
+    fn percentiles_of_a_known_distribution() {
+        let stats = EmitStats::new(true);
+        // 1 ms to 100 ms, recorded in reverse to check they are sorted
+        for ms in (1..=100).rev() {
+            stats.record_at(0, "a", 0, ms * 1000);
+        }
+        let event = &stats.stats_at(0, 1)[0];
+        assert_eq!(event.p50_latency_ms, 51.0);
+        assert_eq!(event.p95_latency_ms, 95.0);
+        assert_eq!(percentile_ms(&[], 0.5), 0.0);
+    }
+}
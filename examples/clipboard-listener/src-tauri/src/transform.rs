@@ -0,0 +1,56 @@
+use base64::Engine;
+
+/// One step of the pipeline applied to clipboard text before it is emitted.
+///
+/// Supported kinds:
+/// - `trim`: strips leading and trailing whitespace
+/// - `base64_decode`: decodes base64 text, text that is not valid base64 or not UTF-8 is kept as is
+/// - `truncate_at`: keeps the first `max_len` characters, e.g. `{ "max_len": 100 }`
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub struct TransformStep {
+    pub kind: String,
+    #[serde(default)]
+    pub config: serde_json::Value,
+}
+
+pub fn validate(steps: &[TransformStep]) -> Result<(), String> {
+    for (index, step) in steps.iter().enumerate() {
+        match step.kind.as_str() {
+            "trim" | "base64_decode" => {}
+            "truncate_at" => {
+                if step
+                    .config
+                    .get("max_len")
+                    .and_then(|v| v.as_u64())
+                    .is_none()
+                {
+                    return Err(format!(
+                        "Transform {} (truncate_at) requires a non-negative integer max_len",
+                        index
+                    ));
+                }
+            }
+            kind => return Err(format!("Transform {} has unknown kind \"{}\"", index, kind)),
+        }
+    }
+    Ok(())
+}
+
+/// Applies the steps in order. Steps are expected to have passed [`validate`].
+pub fn apply(steps: &[TransformStep], text: String) -> String {
+    steps
+        .iter()
+        .fold(text, |text, step| match step.kind.as_str() {
+            "trim" => text.trim().to_string(),
+            "base64_decode" => base64::engine::general_purpose::STANDARD
+                .decode(text.trim())
+                .ok()
+                .and_then(|bytes| String::from_utf8(bytes).ok())
+                .unwrap_or(text),
+            "truncate_at" => match step.config.get("max_len").and_then(|v| v.as_u64()) {
+                Some(max_len) => text.chars().take(max_len as usize).collect(),
+                None => text,
+            },
+            _ => text,
+        })
+}
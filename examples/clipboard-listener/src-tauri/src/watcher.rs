@@ -0,0 +1,632 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use arboard::Clipboard;
+use clipboard_master::{CallbackResult, ClipboardHandler, Master};
+
+/// RGBA pixels read from the clipboard.
+#[derive(Clone, Debug)]
+pub struct ClipboardImage {
+    pub width: usize,
+    pub height: usize,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Clone, Debug)]
+pub enum ClipboardContent {
+    /// Plain text, with the HTML version if the clipboard also holds one.
+    Text {
+        text: String,
+        html: Option<String>,
+    },
+    Image(ClipboardImage),
+}
+
+/// A change of the clipboard content observed by a [`ClipboardWatcher`].
+#[derive(Clone, Debug)]
+pub struct ClipboardChange {
+    pub content: ClipboardContent,
+    /// milliseconds since the unix epoch
+    pub timestamp: u64,
+}
+
+/// What a [`ClipboardWatcher`] reports to its callback.
+#[derive(Clone, Debug)]
+pub enum WatchEvent {
+    Changed(ClipboardChange),
+    /// The clipboard went from content the watcher reads to something it does not read
+    /// (nothing at all, or an image when images are not watched).
+    NonText,
+    /// Reading the clipboard failed. Reported once until the error changes or a read succeeds.
+    Error(String),
+}
+
+#[derive(Debug)]
+pub enum ReadError {
+    /// The clipboard is empty or holds a format that was not asked for.
+    NonText,
+    Failed(String),
+}
+
+/// Where the clipboard is read from.
+pub enum ClipboardSource {
+    Arboard(Clipboard),
+    /// External program such as `xclip`, used when arboard cannot connect to the display server.
+    Command(&'static str, &'static [&'static str]),
+}
+
+impl ClipboardSource {
+    pub fn get_text(&mut self) -> Result<String, ReadError> {
+        match self {
+            ClipboardSource::Arboard(clipboard) => clipboard.get_text().map_err(|err| match err {
+                arboard::Error::ContentNotAvailable => ReadError::NonText,
+                err => ReadError::Failed(err.to_string()),
+            }),
+            ClipboardSource::Command(program, args) => {
+                let output = std::process::Command::new(program)
+                    .args(*args)
+                    .output()
+                    .map_err(|err| ReadError::Failed(err.to_string()))?;
+                // xclip and xsel exit with an error when there is no text to paste
+                if !output.status.success() {
+                    return Err(ReadError::NonText);
+                }
+                Ok(String::from_utf8_lossy(&output.stdout).to_string())
+            }
+        }
+    }
+
+    /// HTML copied alongside the text, e.g. from a browser. Only read through arboard.
+    pub fn get_html(&mut self) -> Option<String> {
+        match self {
+            ClipboardSource::Arboard(clipboard) => clipboard.get().html().ok(),
+            ClipboardSource::Command(..) => None,
+        }
+    }
+
+    /// Only arboard can read images, the command fallback always reports `NonText`.
+    pub fn get_image(&mut self) -> Result<ClipboardImage, ReadError> {
+        match self {
+            ClipboardSource::Arboard(clipboard) => clipboard
+                .get_image()
+                .map(|image| ClipboardImage {
+                    width: image.width,
+                    height: image.height,
+                    bytes: image.bytes.into_owned(),
+                })
+                .map_err(|err| match err {
+                    arboard::Error::ContentNotAvailable => ReadError::NonText,
+                    err => ReadError::Failed(err.to_string()),
+                }),
+            ClipboardSource::Command(..) => Err(ReadError::NonText),
+        }
+    }
+
+    /// Reads text and HTML, then falls back to an image when `images` is set and there is
+    /// no text.
+    pub fn get_content(&mut self, images: bool) -> Result<ClipboardContent, ReadError> {
+        match self.get_text() {
+            Ok(text) => Ok(ClipboardContent::Text {
+                text,
+                html: self.get_html(),
+            }),
+            Err(ReadError::NonText) if images => self.get_image().map(ClipboardContent::Image),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Finds the first of `xclip`/`xsel` that can be spawned.
+#[cfg(target_os = "linux")]
+pub fn command_fallback() -> Option<ClipboardSource> {
+    let candidates: [(&'static str, &'static [&'static str]); 2] = [
+        ("xclip", &["-selection", "clipboard", "-o"]),
+        ("xsel", &["--clipboard", "--output"]),
+    ];
+    candidates
+        .into_iter()
+        .find(|(program, args)| {
+            std::process::Command::new(program)
+                .args(*args)
+                .output()
+                .is_ok()
+        })
+        .map(|(program, args)| ClipboardSource::Command(program, args))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn command_fallback() -> Option<ClipboardSource> {
+    None
+}
+
+/// Set by [`ClipboardWatcher::stop`]. Backends check it between reads and wait on it instead of
+/// sleeping, so a stop wakes them right away.
+#[derive(Default)]
+pub struct StopSignal {
+    state: Mutex<StopState>,
+    condvar: Condvar,
+}
+
+#[derive(Default)]
+struct StopState {
+    stopped: bool,
+    on_stop: Option<Box<dyn FnOnce() + Send>>,
+}
+
+impl StopSignal {
+    pub fn is_stopped(&self) -> bool {
+        self.state.lock().unwrap().stopped
+    }
+
+    /// Waits up to `timeout`, returning early when the watcher is stopped.
+    pub fn wait(&self, timeout: Duration) {
+        let _ = self
+            .condvar
+            .wait_timeout_while(self.state.lock().unwrap(), timeout, |state| !state.stopped)
+            .unwrap();
+    }
+
+    /// Calls `on_stop` when the watcher is stopped, right away if it already is. For backends
+    /// that block somewhere a condvar cannot reach. Replaces a previously set callback.
+    fn on_stop<F: FnOnce() + Send + 'static>(&self, on_stop: F) {
+        let mut state = self.state.lock().unwrap();
+        if state.stopped {
+            drop(state);
+            on_stop();
+        } else {
+            state.on_stop = Some(Box::new(on_stop));
+        }
+    }
+
+    fn stop(&self) {
+        let on_stop = {
+            let mut state = self.state.lock().unwrap();
+            state.stopped = true;
+            state.on_stop.take()
+        };
+        self.condvar.notify_all();
+        if let Some(on_stop) = on_stop {
+            on_stop();
+        }
+    }
+}
+
+/// Decides when the clipboard is read.
+pub trait ClipboardBackend: Send + 'static {
+    /// Blocks until `stop` is set, passing every clipboard read to `on_read`.
+    fn watch(
+        &mut self,
+        stop: &StopSignal,
+        on_read: &mut dyn FnMut(Result<ClipboardContent, ReadError>),
+    );
+}
+
+pub struct PollingBackend {
+    source: ClipboardSource,
+    interval: Duration,
+    images: bool,
+}
+
+impl PollingBackend {
+    pub fn new(source: ClipboardSource, interval: Duration) -> Self {
+        Self {
+            source,
+            interval,
+            images: false,
+        }
+    }
+
+    /// Also read images when the clipboard holds no text.
+    pub fn with_images(mut self, images: bool) -> Self {
+        self.images = images;
+        self
+    }
+}
+
+impl ClipboardBackend for PollingBackend {
+    fn watch(
+        &mut self,
+        stop: &StopSignal,
+        on_read: &mut dyn FnMut(Result<ClipboardContent, ReadError>),
+    ) {
+        while !stop.is_stopped() {
+            on_read(self.source.get_content(self.images));
+            stop.wait(self.interval);
+        }
+    }
+}
+
+/// Whether [`NativeBackend`] can get change notifications here. On Linux they come from the
+/// X server, so a Wayland session without XWayland has to poll.
+pub fn native_notifications_supported() -> bool {
+    !cfg!(target_os = "linux") || std::env::var_os("DISPLAY").is_some()
+}
+
+/// Reads the clipboard when the OS reports a change (a clipboard format listener on Windows,
+/// XFIXES selection events on X11, the pasteboard change count on macOS).
+///
+/// Falls back to polling if the notifications cannot be set up, `on_fallback` is called with
+/// the reason before that happens.
+pub struct NativeBackend {
+    polling: PollingBackend,
+    on_fallback: Box<dyn FnMut(String) + Send>,
+}
+
+impl NativeBackend {
+    /// `polling` is used as is when notifications are unavailable.
+    pub fn new<F>(polling: PollingBackend, on_fallback: F) -> Self
+    where
+        F: FnMut(String) + Send + 'static,
+    {
+        Self {
+            polling,
+            on_fallback: Box::new(on_fallback),
+        }
+    }
+}
+
+struct NotificationHandler<'a> {
+    source: &'a mut ClipboardSource,
+    images: bool,
+    stop: &'a StopSignal,
+    on_read: &'a mut dyn FnMut(Result<ClipboardContent, ReadError>),
+}
+
+impl ClipboardHandler for NotificationHandler<'_> {
+    fn on_clipboard_change(&mut self) -> CallbackResult {
+        if self.stop.is_stopped() {
+            return CallbackResult::Stop;
+        }
+        (self.on_read)(self.source.get_content(self.images));
+        CallbackResult::Next
+    }
+
+    /// Errors usually persist (the X connection is gone), so the master is stopped rather
+    /// than retried in a busy loop, and `NativeBackend::watch` falls back to polling.
+    fn on_clipboard_error(&mut self, error: std::io::Error) -> CallbackResult {
+        if !self.stop.is_stopped() {
+            (self.on_read)(Err(ReadError::Failed(error.to_string())));
+        }
+        CallbackResult::Stop
+    }
+}
+
+impl ClipboardBackend for NativeBackend {
+    fn watch(
+        &mut self,
+        stop: &StopSignal,
+        on_read: &mut dyn FnMut(Result<ClipboardContent, ReadError>),
+    ) {
+        // notifications only arrive for later changes, read once so there is a baseline
+        on_read(self.polling.source.get_content(self.polling.images));
+        let handler = NotificationHandler {
+            source: &mut self.polling.source,
+            images: self.polling.images,
+            stop,
+            on_read: &mut *on_read,
+        };
+        let result = Master::new(handler).and_then(|mut master| {
+            // stopping shuts the master down, so the thread exits without another notification
+            let shutdown = Arc::new(Mutex::new(Some(master.shutdown_channel())));
+            let stop_shutdown = shutdown.clone();
+            stop.on_stop(move || {
+                if let Some(shutdown) = stop_shutdown.lock().unwrap().take() {
+                    shutdown.signal();
+                }
+            });
+            let result = master.run();
+            // the master is gone once this returns, a later stop must not signal it
+            shutdown.lock().unwrap().take();
+            result
+        });
+        if !stop.is_stopped() {
+            let reason = match result {
+                Ok(()) => "clipboard notifications ended".to_string(),
+                Err(err) => err.to_string(),
+            };
+            (self.on_fallback)(reason);
+            self.polling.watch(stop, on_read);
+        }
+    }
+}
+
+/// Line endings differ between platforms and clipboard tools, compare and emit them as `\n`.
+fn normalize(text: String) -> String {
+    if text.contains("\r\n") {
+        text.replace("\r\n", "\n")
+    } else {
+        text
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// What the change detector remembers about the last read. Images are only kept as a hash
+/// of their pixels so large screenshots are not held in memory twice.
+#[derive(PartialEq)]
+enum ContentKey {
+    /// Both versions, so a change of the formatting alone is still reported.
+    Text(String, Option<String>),
+    Image(u64),
+}
+
+/// Hash of the text as the watcher reports it, i.e. with `\n` line endings. HTML is left out,
+/// platforms may rewrite it when it is read back.
+pub fn text_hash(text: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    text.replace("\r\n", "\n").hash(&mut hasher);
+    hasher.finish()
+}
+
+pub fn image_hash(image: &ClipboardImage) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (image.width, image.height).hash(&mut hasher);
+    image.bytes.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Turns raw clipboard reads into [`WatchEvent`]s.
+///
+/// The first read only sets the baseline. Unreadable content is reported once when it
+/// replaces readable content, and the next read is always reported as a change, even if it
+/// equals the content from before. Failed reads keep the last known content.
+#[derive(Default)]
+struct ChangeDetector {
+    primed: bool,
+    /// `None` while the clipboard holds nothing the watcher reads
+    previous: Option<ContentKey>,
+    last_error: Option<String>,
+}
+
+impl ChangeDetector {
+    fn observe(&mut self, read: Result<ClipboardContent, ReadError>) -> Option<WatchEvent> {
+        let primed = std::mem::replace(&mut self.primed, true);
+        match read {
+            Ok(content) => {
+                self.last_error = None;
+                let (content, key) = match content {
+                    ClipboardContent::Text { text, html } => {
+                        let text = normalize(text);
+                        let html = html.map(normalize);
+                        let key = ContentKey::Text(text.clone(), html.clone());
+                        (ClipboardContent::Text { text, html }, key)
+                    }
+                    ClipboardContent::Image(image) => {
+                        let key = ContentKey::Image(image_hash(&image));
+                        (ClipboardContent::Image(image), key)
+                    }
+                };
+                if self.previous.as_ref() == Some(&key) {
+                    return None;
+                }
+                self.previous = Some(key);
+                primed.then(|| {
+                    WatchEvent::Changed(ClipboardChange {
+                        content,
+                        timestamp: now_millis(),
+                    })
+                })
+            }
+            Err(ReadError::NonText) => {
+                self.last_error = None;
+                let was_readable = self.previous.take().is_some();
+                (primed && was_readable).then_some(WatchEvent::NonText)
+            }
+            Err(ReadError::Failed(message)) => {
+                if self.last_error.as_ref() == Some(&message) {
+                    return None;
+                }
+                self.last_error = Some(message.clone());
+                Some(WatchEvent::Error(message))
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct ExitState {
+    exited: bool,
+    on_exit: Option<Box<dyn FnOnce() + Send>>,
+}
+
+/// Set when the watcher thread ends, also when it panics.
+struct ExitSignal(Arc<(Mutex<ExitState>, Condvar)>);
+
+impl Drop for ExitSignal {
+    fn drop(&mut self) {
+        let (state, condvar) = &*self.0;
+        let on_exit = {
+            let mut state = state.lock().unwrap();
+            state.exited = true;
+            state.on_exit.take()
+        };
+        condvar.notify_all();
+        if let Some(on_exit) = on_exit {
+            on_exit();
+        }
+    }
+}
+
+/// Runs a [`ClipboardBackend`] on its own thread and reports changes through a callback.
+pub struct ClipboardWatcher {
+    stop: Arc<StopSignal>,
+    exited: Arc<(Mutex<ExitState>, Condvar)>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl ClipboardWatcher {
+    pub fn start<B, F>(mut backend: B, mut on_change: F) -> Self
+    where
+        B: ClipboardBackend,
+        F: FnMut(WatchEvent) + Send + 'static,
+    {
+        let stop = Arc::new(StopSignal::default());
+        let exited = Arc::new((Mutex::new(ExitState::default()), Condvar::new()));
+        let thread_stop = stop.clone();
+        let exit_signal = ExitSignal(exited.clone());
+        let handle = std::thread::spawn(move || {
+            let _exit_signal = exit_signal;
+            let mut detector = ChangeDetector::default();
+            backend.watch(&thread_stop, &mut |read| {
+                if let Some(event) = detector.observe(read) {
+                    on_change(event);
+                }
+            });
+        });
+        Self {
+            stop,
+            exited,
+            handle: Some(handle),
+        }
+    }
+
+    pub fn has_exited(&self) -> bool {
+        self.exited.0.lock().unwrap().exited
+    }
+
+    /// Calls `on_exit` once the thread has exited, right away if it already has. Replaces a
+    /// previously set callback.
+    pub fn on_exit<F: FnOnce() + Send + 'static>(&self, on_exit: F) {
+        let mut state = self.exited.0.lock().unwrap();
+        if state.exited {
+            drop(state);
+            on_exit();
+        } else {
+            state.on_exit = Some(Box::new(on_exit));
+        }
+    }
+
+    /// Signals the thread to stop and waits up to `timeout` for it to exit.
+    ///
+    /// Returns whether the thread has exited.
+    pub fn stop(&mut self, timeout: Duration) -> bool {
+        self.stop.stop();
+        let (state, condvar) = &*self.exited;
+        let exited = condvar
+            .wait_timeout_while(state.lock().unwrap(), timeout, |state| !state.exited)
+            .unwrap()
+            .0
+            .exited;
+        if exited {
+            if let Some(handle) = self.handle.take() {
+                let _ = handle.join();
+            }
+        }
+        exited
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> Result<ClipboardContent, ReadError> {
+        Ok(ClipboardContent::Text {
+            text: text.to_string(),
+            html: None,
+        })
+    }
+
+    fn changed_text(event: Option<WatchEvent>) -> Option<String> {
+        match event {
+            Some(WatchEvent::Changed(ClipboardChange {
+                content: ClipboardContent::Text { text, .. },
+                ..
+            })) => Some(text),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn normalize_converts_crlf() {
+        assert_eq!(normalize("a\r\nb\r\n".to_string()), "a\nb\n");
+        assert_eq!(normalize("a\nb".to_string()), "a\nb");
+    }
+
+    #[test]
+    fn first_read_only_sets_baseline() {
+        let mut detector = ChangeDetector::default();
+        assert!(detector.observe(text("a")).is_none());
+        assert_eq!(
+            changed_text(detector.observe(text("b"))).as_deref(),
+            Some("b")
+        );
+    }
+
+    #[test]
+    fn identical_content_is_not_reported_again() {
+        let mut detector = ChangeDetector::default();
+        detector.observe(text("a"));
+        assert_eq!(
+            changed_text(detector.observe(text("b"))).as_deref(),
+            Some("b")
+        );
+        assert!(detector.observe(text("b")).is_none());
+    }
+
+    #[test]
+    fn line_endings_do_not_count_as_a_change() {
+        let mut detector = ChangeDetector::default();
+        detector.observe(text("a\r\nb"));
+        assert!(detector.observe(text("a\nb")).is_none());
+        assert!(detector.observe(text("a\r\nb")).is_none());
+    }
+
+    #[test]
+    fn non_text_is_reported_once_and_then_text_again() {
+        let mut detector = ChangeDetector::default();
+        detector.observe(text("a"));
+        assert!(matches!(
+            detector.observe(Err(ReadError::NonText)),
+            Some(WatchEvent::NonText)
+        ));
+        assert!(detector.observe(Err(ReadError::NonText)).is_none());
+        assert_eq!(
+            changed_text(detector.observe(text("a"))).as_deref(),
+            Some("a")
+        );
+    }
+
+    #[test]
+    fn repeated_error_is_reported_once() {
+        let mut detector = ChangeDetector::default();
+        let failed = || Err(ReadError::Failed("busy".to_string()));
+        assert!(
+            matches!(detector.observe(failed()), Some(WatchEvent::Error(message)) if message == "busy")
+        );
+        assert!(detector.observe(failed()).is_none());
+    }
+
+    /// Waits like [`PollingBackend`], with an interval far longer than the test.
+    struct WaitingBackend;
+
+    impl ClipboardBackend for WaitingBackend {
+        fn watch(
+            &mut self,
+            stop: &StopSignal,
+            _on_read: &mut dyn FnMut(Result<ClipboardContent, ReadError>),
+        ) {
+            while !stop.is_stopped() {
+                stop.wait(Duration::from_secs(60));
+            }
+        }
+    }
+
+    #[test]
+    fn stop_wakes_a_waiting_backend() {
+        let mut watcher = ClipboardWatcher::start(WaitingBackend, |_| {});
+        let (sender, receiver) = std::sync::mpsc::channel();
+        watcher.on_exit(move || sender.send(()).unwrap());
+        assert!(watcher.stop(Duration::from_secs(1)));
+        assert!(watcher.has_exited());
+        assert!(receiver.try_recv().is_ok());
+    }
+}
@@ -1,13 +1,29 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
+
+// Migrating from Tauri v1 to v2 (https://v2.tauri.app/start/migrate/from-tauri-1/)
+// - `emit_all(event, payload)` is now `emit(event, payload)` from the `Emitter` trait
+// - `tauri::Window` is now `tauri::WebviewWindow`, `get_window` is now `get_webview_window`
+// - `app.path_resolver().app_data_dir()` is now `app.path().app_data_dir()` and returns a `Result`
+// - `tauri.conf.json` moved to the v2 layout, the allowlist is replaced by `capabilities/`
+// - on the frontend `invoke` is imported from `@tauri-apps/api/core`
 use std::collections::HashMap;
 
-use tauri::Manager;
+use tauri::{Emitter, Manager, Runtime};
 
-// Learn more about Tauri commands at https://tauri.app/v1/guides/features/command
+// Learn more about Tauri commands at https://v2.tauri.app/develop/calling-rust/
+// `lang` is a language code such as "es", unknown or missing languages fall back to English
 #[tauri::command]
-fn greet(first_name: &str) -> String {
-    format!("Hello, {}! You've been greeted from Rust!", first_name)
+fn greet(first_name: &str, lang: Option<&str>) -> String {
+    match lang.unwrap_or("en") {
+        "es" => format!("¡Hola, {}! Has sido saludado desde Rust!", first_name),
+        "fr" => format!(
+            "Bonjour, {}! Vous avez été accueilli depuis Rust!",
+            first_name
+        ),
+        "de" => format!("Hallo, {}! Du wurdest von Rust begrüßt!", first_name),
+        _ => format!("Hello, {}! You've been greeted from Rust!", first_name),
+    }
 }
 
 // custom data structure must be Deserialize as a parameter and Serialize as a return value
@@ -36,17 +52,52 @@ async fn my_ip() -> Result<HashMap<String, String>, String> {
 }
 
 #[tauri::command]
-fn window_label(window: tauri::Window) -> String {
+fn window_label<R: Runtime>(window: tauri::WebviewWindow<R>) -> String {
     window.label().to_string()
 }
 
+// Every field is picked explicitly. Do not serialize the whole config or the process
+// environment here, so adding a field is always a deliberate decision.
+#[derive(serde::Serialize)]
+struct AppInfo {
+    name: String,
+    version: String,
+    identifier: String,
+    tauri_version: String,
+    platform: String,
+    arch: String,
+    app_data_dir: Option<String>,
+    app_config_dir: Option<String>,
+    app_log_dir: Option<String>,
+}
+
+#[tauri::command]
+fn get_app_info<R: Runtime>(app_handle: tauri::AppHandle<R>) -> AppInfo {
+    let package_info = app_handle.package_info();
+    let path_resolver = app_handle.path();
+    let path_to_string = |path: tauri::Result<std::path::PathBuf>| {
+        path.ok().map(|p| p.to_string_lossy().to_string())
+    };
+    AppInfo {
+        name: package_info.name.clone(),
+        version: package_info.version.to_string(),
+        identifier: app_handle.config().identifier.clone(),
+        tauri_version: tauri::VERSION.to_string(),
+        platform: std::env::consts::OS.to_string(),
+        arch: std::env::consts::ARCH.to_string(),
+        app_data_dir: path_to_string(path_resolver.app_data_dir()),
+        app_config_dir: path_to_string(path_resolver.app_config_dir()),
+        app_log_dir: path_to_string(path_resolver.app_log_dir()),
+    }
+}
+
 #[derive(Default)]
 struct CounterMut {
     count: std::sync::Mutex<i32>,
 }
 #[tauri::command]
-fn event_and_state_increment_mut(
-    app_handle: tauri::AppHandle,
+fn event_and_state_increment_mut<R: Runtime>(
+    app_handle: tauri::AppHandle<R>,
     count_state: tauri::State<'_, CounterMut>,
 ) {
     // https://doc.rust-lang.org/std/sync/struct.MutexGuard.html
@@ -54,7 +105,7 @@ fn event_and_state_increment_mut(
     *count_state.count.lock().unwrap() += 1;
     let count = *count_state.count.lock().unwrap();
     app_handle
-        .emit_all("event_and_state_increment_mut", count)
+        .emit("event_and_state_increment_mut", count)
         .unwrap();
 }
 
@@ -67,8 +118,8 @@ fn state(count_state: tauri::State<'_, Counter>) -> i32 {
     count_state.0
 }
 
-fn main() {
-    tauri::Builder::default()
+fn build_app<R: Runtime>(builder: tauri::Builder<R>) -> tauri::Builder<R> {
+    builder
         .manage(CounterMut { count: 0.into() })
         .manage(Counter(0.into()))
         .invoke_handler(tauri::generate_handler![
@@ -77,8 +128,128 @@ fn main() {
             my_ip,
             window_label,
             state,
-            event_and_state_increment_mut
+            event_and_state_increment_mut,
+            get_app_info
         ])
+}
+
+fn main() {
+    build_app(tauri::Builder::default())
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    use serde_json::{json, Value};
+    use tauri::ipc::{CallbackFn, InvokeBody};
+    use tauri::test::{mock_builder, mock_context, noop_assets, MockRuntime, INVOKE_KEY};
+    use tauri::webview::InvokeRequest;
+    use tauri::{App, Listener, WebviewWindow, WebviewWindowBuilder};
+
+    use super::build_app;
+
+    fn mock_app() -> (App<MockRuntime>, WebviewWindow<MockRuntime>) {
+        let app = build_app(mock_builder())
+            .build(mock_context(noop_assets()))
+            .unwrap();
+        let webview = WebviewWindowBuilder::new(&app, "main", Default::default())
+            .build()
+            .unwrap();
+        (app, webview)
+    }
+
+    fn invoke(webview: &WebviewWindow<MockRuntime>, cmd: &str, args: Value) -> Value {
+        tauri::test::get_ipc_response(
+            webview,
+            InvokeRequest {
+                cmd: cmd.into(),
+                callback: CallbackFn(0),
+                error: CallbackFn(1),
+                url: "tauri://localhost".parse().unwrap(),
+                body: InvokeBody::Json(args),
+                headers: Default::default(),
+                invoke_key: INVOKE_KEY.to_string(),
+            },
+        )
+        .unwrap()
+        .deserialize()
+        .unwrap()
+    }
+
+    #[test]
+    fn greet_round_trip() {
+        let (_app, webview) = mock_app();
+        assert_eq!(
+            invoke(&webview, "greet", json!({ "firstName": "Ada" })),
+            json!("Hello, Ada! You've been greeted from Rust!")
+        );
+    }
+
+    #[test]
+    fn greet_picks_language() {
+        let (_app, webview) = mock_app();
+        let greet = |lang: Value| {
+            invoke(
+                &webview,
+                "greet",
+                json!({ "firstName": "Ada", "lang": lang }),
+            )
+        };
+        assert_eq!(
+            greet(Value::Null),
+            json!("Hello, Ada! You've been greeted from Rust!")
+        );
+        assert_eq!(
+            greet(json!("en")),
+            json!("Hello, Ada! You've been greeted from Rust!")
+        );
+        assert_eq!(
+            greet(json!("es")),
+            json!("¡Hola, Ada! Has sido saludado desde Rust!")
+        );
+        assert_eq!(
+            greet(json!("fr")),
+            json!("Bonjour, Ada! Vous avez été accueilli depuis Rust!")
+        );
+        assert_eq!(
+            greet(json!("de")),
+            json!("Hallo, Ada! Du wurdest von Rust begrüßt!")
+        );
+        assert_eq!(
+            greet(json!("xx")),
+            json!("Hello, Ada! You've been greeted from Rust!")
+        );
+    }
+
+    #[test]
+    fn custom_payload_round_trip() {
+        let (_app, webview) = mock_app();
+        assert_eq!(
+            invoke(
+                &webview,
+                "custom_payload",
+                json!({ "payload": { "name": "Ada" } })
+            ),
+            json!({ "name": "Hello, Ada! You've been greeted from Rust!" })
+        );
+    }
+
+    #[test]
+    fn increment_emits_new_count() {
+        let (app, webview) = mock_app();
+        let (sender, receiver) = channel();
+        app.listen_any("event_and_state_increment_mut", move |event| {
+            sender.send(event.payload().to_string()).unwrap();
+        });
+
+        for expected in ["1", "2"] {
+            invoke(&webview, "event_and_state_increment_mut", json!({}));
+            let payload = receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+            assert_eq!(payload, expected);
+        }
+    }
+}